@@ -0,0 +1,252 @@
+use anyhow::{Context, Result};
+use git2::Oid;
+use heed::types::{Bytes, OwnedType};
+use heed::{Database, Env, RwTxn};
+
+use super::{object_type_from_int, object_type_to_int, Backend, CommitRecord, TreeEntryRecord};
+
+/// A memory-mapped KV alternative to [`super::SqliteBackend`], for
+/// monorepos where SQLite's relational overhead on `tree_entries` starts to
+/// hurt. Everything is keyed by the 20-byte object OID:
+///
+/// - `blobs`: oid -> the same lz4-compressed payload SQLite stores.
+/// - `tree_entries`: `tree_oid ++ name` -> `(kind, oid, mode)`, one row per
+///   entry (not one row per tree), so adding an entry is a single keyed
+///   write instead of a decode-append-encode of the whole tree's entries.
+/// - `commits`: commit oid -> the commit's metadata fields, encoded.
+pub struct LmdbBackend<'env> {
+    txn: RwTxn<'env>,
+    commits: Database<OwnedType<[u8; 20]>, Bytes>,
+    tree_entries: Database<Bytes, Bytes>,
+    blobs: Database<OwnedType<[u8; 20]>, Bytes>,
+}
+
+impl<'env> LmdbBackend<'env> {
+    pub fn new(env: &'env Env) -> Result<Self> {
+        let mut txn = env.write_txn()?;
+        let commits = env.create_database(&mut txn, Some("commits"))?;
+        let tree_entries = env.create_database(&mut txn, Some("tree_entries"))?;
+        let blobs = env.create_database(&mut txn, Some("blobs"))?;
+
+        Ok(Self {
+            txn,
+            commits,
+            tree_entries,
+            blobs,
+        })
+    }
+
+    /// Flush and commit the underlying LMDB write transaction. Ingestion
+    /// callers should call this once at the end of a run, the same way a
+    /// `rusqlite::Transaction` is committed for [`super::SqliteBackend`].
+    pub fn commit(self) -> Result<()> {
+        self.txn.commit().context("committing lmdb transaction")
+    }
+}
+
+impl<'env> Backend for LmdbBackend<'env> {
+    fn put_tree_entry(&mut self, entry: &TreeEntryRecord) -> Result<bool> {
+        let key = tree_entry_key(entry.tree_oid, &entry.name);
+        if self.tree_entries.get(&self.txn, &key)?.is_some() {
+            return Ok(false);
+        }
+
+        let value = encode_tree_entry(entry);
+        self.tree_entries.put(&mut self.txn, &key, &value)?;
+        Ok(true)
+    }
+
+    fn put_commit(&mut self, commit: &CommitRecord) -> Result<()> {
+        let encoded = encode_commit(commit);
+        self.commits
+            .put(&mut self.txn, commit.oid.as_bytes().try_into()?, &encoded)?;
+        Ok(())
+    }
+
+    fn blob_exists(&mut self, oid: Oid) -> Result<bool> {
+        Ok(self.blobs.get(&self.txn, oid.as_bytes().try_into()?)?.is_some())
+    }
+
+    fn put_blob(&mut self, oid: Oid, content_lz4: &[u8]) -> Result<()> {
+        self.blobs
+            .put(&mut self.txn, oid.as_bytes().try_into()?, content_lz4)?;
+        Ok(())
+    }
+}
+
+/// Stream every commit stored in `env`, in no particular order, to `f`.
+/// Used by [`crate::convert`] to copy a store into a different backend.
+pub fn for_each_commit(env: &Env, mut f: impl FnMut(CommitRecord) -> Result<()>) -> Result<()> {
+    let rtxn = env.read_txn()?;
+    let commits: Database<OwnedType<[u8; 20]>, Bytes> =
+        env.open_database(&rtxn, Some("commits"))?.context("no commits database")?;
+    for entry in commits.iter(&rtxn)? {
+        let (oid, raw) = entry?;
+        f(decode_commit(Oid::from_bytes(&oid)?, raw)?)?;
+    }
+    Ok(())
+}
+
+/// Stream every tree entry stored in `env`, in no particular order, to `f`.
+pub fn for_each_tree_entry(
+    env: &Env,
+    mut f: impl FnMut(TreeEntryRecord) -> Result<()>,
+) -> Result<()> {
+    let rtxn = env.read_txn()?;
+    let tree_entries: Database<Bytes, Bytes> = env
+        .open_database(&rtxn, Some("tree_entries"))?
+        .context("no tree_entries database")?;
+    for entry in tree_entries.iter(&rtxn)? {
+        let (key, value) = entry?;
+        f(decode_tree_entry(key, value)?)?;
+    }
+    Ok(())
+}
+
+/// Stream every `(oid, content_lz4)` pair stored in `env` to `f`, without
+/// decompressing.
+pub fn for_each_blob(env: &Env, mut f: impl FnMut(Oid, Vec<u8>) -> Result<()>) -> Result<()> {
+    let rtxn = env.read_txn()?;
+    let blobs: Database<OwnedType<[u8; 20]>, Bytes> =
+        env.open_database(&rtxn, Some("blobs"))?.context("no blobs database")?;
+    for entry in blobs.iter(&rtxn)? {
+        let (oid, raw) = entry?;
+        f(Oid::from_bytes(&oid)?, raw.to_vec())?;
+    }
+    Ok(())
+}
+
+fn decode_commit(oid: Oid, raw: &[u8]) -> Result<CommitRecord> {
+    let tree_oid = Oid::from_bytes(&raw[0..20])?;
+    let author_date = i64::from_le_bytes(raw[20..28].try_into()?);
+    let committer_date = i64::from_le_bytes(raw[28..36].try_into()?);
+
+    let mut pos = 36;
+    let mut fields = Vec::with_capacity(6);
+    for _ in 0..6 {
+        let len = u32::from_le_bytes(raw[pos..pos + 4].try_into()?) as usize;
+        pos += 4;
+        fields.push(raw[pos..pos + len].to_vec());
+        pos += len;
+    }
+    let mut fields = fields.into_iter();
+    Ok(CommitRecord {
+        oid,
+        tree_oid,
+        message: fields.next().unwrap(),
+        parents: fields.next().unwrap(),
+        author_name: fields.next().unwrap(),
+        author_email: fields.next().unwrap(),
+        author_date,
+        committer_name: fields.next().unwrap(),
+        committer_email: fields.next().unwrap(),
+        committer_date,
+    })
+}
+
+/// `tree_oid ++ name`. `tree_oid` is a fixed 20 bytes, so this prefix
+/// uniquely scopes a tree's entries without any delimiter ambiguity.
+fn tree_entry_key(tree_oid: Oid, name: &[u8]) -> Vec<u8> {
+    let mut key = Vec::with_capacity(20 + name.len());
+    key.extend_from_slice(tree_oid.as_bytes());
+    key.extend_from_slice(name);
+    key
+}
+
+fn encode_tree_entry(entry: &TreeEntryRecord) -> Vec<u8> {
+    let mut out = Vec::with_capacity(25);
+    out.push(object_type_to_int(entry.kind));
+    out.extend_from_slice(entry.oid.as_bytes());
+    out.extend_from_slice(&entry.mode.to_le_bytes());
+    out
+}
+
+fn decode_tree_entry(key: &[u8], value: &[u8]) -> Result<TreeEntryRecord> {
+    let tree_oid = Oid::from_bytes(&key[0..20])?;
+    let name = key[20..].to_vec();
+    let kind = object_type_from_int(value[0]);
+    let oid = Oid::from_bytes(&value[1..21])?;
+    let mode = i32::from_le_bytes(value[21..25].try_into()?);
+    Ok(TreeEntryRecord {
+        tree_oid,
+        name,
+        kind,
+        oid,
+        mode,
+    })
+}
+
+fn encode_commit(commit: &CommitRecord) -> Vec<u8> {
+    let mut out = Vec::new();
+    out.extend_from_slice(commit.tree_oid.as_bytes());
+    out.extend_from_slice(&commit.author_date.to_le_bytes());
+    out.extend_from_slice(&commit.committer_date.to_le_bytes());
+    for field in [
+        &commit.message,
+        &commit.parents,
+        &commit.author_name,
+        &commit.author_email,
+        &commit.committer_name,
+        &commit.committer_email,
+    ] {
+        out.extend_from_slice(&(field.len() as u32).to_le_bytes());
+        out.extend_from_slice(field);
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use git2::ObjectType;
+
+    #[test]
+    fn commit_round_trips() {
+        let commit = CommitRecord {
+            oid: Oid::from_bytes(&[1; 20]).unwrap(),
+            tree_oid: Oid::from_bytes(&[2; 20]).unwrap(),
+            message: b"msg".to_vec(),
+            parents: [3u8; 20].to_vec(),
+            author_name: b"alice".to_vec(),
+            author_email: b"alice@example.com".to_vec(),
+            author_date: 1_700_000_000,
+            committer_name: b"bob".to_vec(),
+            committer_email: b"bob@example.com".to_vec(),
+            committer_date: 1_700_000_100,
+        };
+
+        let encoded = encode_commit(&commit);
+        let decoded = decode_commit(commit.oid, &encoded).unwrap();
+
+        assert_eq!(decoded.tree_oid, commit.tree_oid);
+        assert_eq!(decoded.message, commit.message);
+        assert_eq!(decoded.parents, commit.parents);
+        assert_eq!(decoded.author_name, commit.author_name);
+        assert_eq!(decoded.author_email, commit.author_email);
+        assert_eq!(decoded.author_date, commit.author_date);
+        assert_eq!(decoded.committer_name, commit.committer_name);
+        assert_eq!(decoded.committer_email, commit.committer_email);
+        assert_eq!(decoded.committer_date, commit.committer_date);
+    }
+
+    #[test]
+    fn tree_entry_round_trips() {
+        let entry = TreeEntryRecord {
+            tree_oid: Oid::from_bytes(&[4; 20]).unwrap(),
+            name: b"src/main.rs".to_vec(),
+            kind: Some(ObjectType::Blob),
+            oid: Oid::from_bytes(&[5; 20]).unwrap(),
+            mode: 0o100755,
+        };
+
+        let key = tree_entry_key(entry.tree_oid, &entry.name);
+        let value = encode_tree_entry(&entry);
+        let decoded = decode_tree_entry(&key, &value).unwrap();
+
+        assert_eq!(decoded.tree_oid, entry.tree_oid);
+        assert_eq!(decoded.name, entry.name);
+        assert_eq!(decoded.kind, entry.kind);
+        assert_eq!(decoded.oid, entry.oid);
+        assert_eq!(decoded.mode, entry.mode);
+    }
+}
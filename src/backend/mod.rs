@@ -0,0 +1,85 @@
+pub(crate) mod lmdb;
+pub(crate) mod sqlite;
+
+pub use lmdb::LmdbBackend;
+pub use sqlite::SqliteBackend;
+
+use anyhow::Result;
+use git2::{ObjectType, Oid};
+
+/// A single row to be written to the `commits` table (or equivalent).
+#[derive(Debug, Clone)]
+pub struct CommitRecord {
+    pub oid: Oid,
+    pub tree_oid: Oid,
+    pub message: Vec<u8>,
+    pub parents: Vec<u8>,
+    pub author_name: Vec<u8>,
+    pub author_email: Vec<u8>,
+    pub author_date: i64,
+    pub committer_name: Vec<u8>,
+    pub committer_email: Vec<u8>,
+    pub committer_date: i64,
+}
+
+/// A single row to be written to the `tree_entries` table (or equivalent).
+#[derive(Debug, Clone)]
+pub struct TreeEntryRecord {
+    pub tree_oid: Oid,
+    pub name: Vec<u8>,
+    pub kind: Option<ObjectType>,
+    pub oid: Oid,
+    /// git's filemode for this entry (e.g. `0o100644`, `0o100755`,
+    /// `0o120000`), so callers that reconstruct a worktree (like
+    /// [`crate::archive`]) can tell an executable blob or a symlink apart
+    /// from a plain file.
+    pub mode: i32,
+}
+
+/// Storage operations an [`crate::ingest::Ingestor`] needs, extracted so
+/// ingestion isn't hard-coded to SQLite. One `Backend` is constructed per
+/// write transaction (for [`crate::ingest::BatchIngestor`], one per batch)
+/// and dropped (or committed) when it's done.
+pub trait Backend {
+    /// Insert `entry` if it's not already present. Returns `true` if this
+    /// call actually added a new row, mirroring the old `INSERT OR IGNORE
+    /// ... RETURNING *` check, so the caller knows whether to recurse into
+    /// a tree it hasn't visited before.
+    fn put_tree_entry(&mut self, entry: &TreeEntryRecord) -> Result<bool>;
+
+    fn put_commit(&mut self, commit: &CommitRecord) -> Result<()>;
+
+    fn blob_exists(&mut self, oid: Oid) -> Result<bool>;
+
+    fn put_blob(&mut self, oid: Oid, content_lz4: &[u8]) -> Result<()>;
+
+    /// Add `oid`'s decompressed text to the full-text search index, if this
+    /// backend maintains one. Backends that don't support search (e.g.
+    /// [`LmdbBackend`]) can leave this as a no-op.
+    fn index_text(&mut self, _oid: Oid, _text: &[u8]) -> Result<()> {
+        Ok(())
+    }
+}
+
+pub(crate) fn object_type_to_int(kind: Option<ObjectType>) -> u8 {
+    match kind {
+        None => 0,
+        Some(ObjectType::Any) => 1,
+        Some(ObjectType::Commit) => 2,
+        Some(ObjectType::Tree) => 3,
+        Some(ObjectType::Blob) => 4,
+        Some(ObjectType::Tag) => 5,
+    }
+}
+
+pub(crate) fn object_type_from_int(val: u8) -> Option<ObjectType> {
+    match val {
+        0 => None,
+        1 => Some(ObjectType::Any),
+        2 => Some(ObjectType::Commit),
+        3 => Some(ObjectType::Tree),
+        4 => Some(ObjectType::Blob),
+        5 => Some(ObjectType::Tag),
+        _ => panic!("unknown"),
+    }
+}
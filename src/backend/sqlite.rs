@@ -0,0 +1,194 @@
+use anyhow::Result;
+use git2::Oid;
+use rusqlite::{Connection, Statement, Transaction};
+
+use super::{object_type_from_int, object_type_to_int, Backend, CommitRecord, TreeEntryRecord};
+
+/// The original, relational backend: ingests into the `commits`,
+/// `tree_entries` and `blobs` tables of a SQLite database.
+pub struct SqliteBackend<'tx> {
+    tx: &'tx Transaction<'tx>,
+    tree_entry_stmt: Statement<'tx>,
+    commit_stmt: Statement<'tx>,
+    blob_exists_stmt: Statement<'tx>,
+    blob_insert_stmt: Statement<'tx>,
+}
+
+impl<'tx> SqliteBackend<'tx> {
+    pub fn new(tx: &'tx Transaction<'tx>) -> Result<Self> {
+        let tree_entry_stmt = tx.prepare(
+            "INSERT OR IGNORE INTO tree_entries (
+                tree_oid,
+                name,
+                kind,
+                oid,
+                mode
+            ) VALUES (?, ?, ?, ?, ?)
+            RETURNING *",
+        )?;
+        let commit_stmt = tx.prepare(
+            "INSERT OR IGNORE INTO commits (
+                oid,
+                tree_oid,
+                message,
+                parents,
+                author_name,
+                author_email,
+                author_date,
+                committer_name,
+                committer_email,
+                committer_date
+            ) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?)",
+        )?;
+        let blob_exists_stmt = tx.prepare("SELECT EXISTS (SELECT * FROM blobs WHERE oid = ?);")?;
+        let blob_insert_stmt = tx.prepare(
+            "INSERT OR IGNORE INTO blobs (oid, content_lz4)
+            VALUES (?, ?)",
+        )?;
+
+        Ok(Self {
+            tx,
+            tree_entry_stmt,
+            commit_stmt,
+            blob_exists_stmt,
+            blob_insert_stmt,
+        })
+    }
+}
+
+impl<'tx> Backend for SqliteBackend<'tx> {
+    fn put_tree_entry(&mut self, entry: &TreeEntryRecord) -> Result<bool> {
+        let mut rows = self.tree_entry_stmt.query((
+            entry.tree_oid.as_bytes(),
+            entry.name.as_slice(),
+            object_type_to_int(entry.kind),
+            entry.oid.as_bytes(),
+            entry.mode,
+        ))?;
+        Ok(rows.next()?.is_some())
+    }
+
+    fn put_commit(&mut self, commit: &CommitRecord) -> Result<()> {
+        self.commit_stmt.execute((
+            commit.oid.as_bytes(),
+            commit.tree_oid.as_bytes(),
+            commit.message.as_slice(),
+            commit.parents.as_slice(),
+            commit.author_name.as_slice(),
+            commit.author_email.as_slice(),
+            commit.author_date,
+            commit.committer_name.as_slice(),
+            commit.committer_email.as_slice(),
+            commit.committer_date,
+        ))?;
+        Ok(())
+    }
+
+    fn blob_exists(&mut self, oid: Oid) -> Result<bool> {
+        Ok(self
+            .blob_exists_stmt
+            .query_row((oid.as_bytes(),), |row| row.get::<_, bool>(0))?)
+    }
+
+    fn put_blob(&mut self, oid: Oid, content_lz4: &[u8]) -> Result<()> {
+        self.blob_insert_stmt.execute((oid.as_bytes(), content_lz4))?;
+        Ok(())
+    }
+
+    fn index_text(&mut self, oid: Oid, text: &[u8]) -> Result<()> {
+        index_blob_text(self.tx, oid, text)
+    }
+}
+
+/// Index `text` for `oid` in the `blob_fts` virtual table, unless it looks
+/// binary. `blob_fts` is contentless (it doesn't duplicate `blobs.content_lz4`),
+/// so rows are keyed by an explicit rowid tracked in `blob_fts_oid`.
+pub fn index_blob_text(conn: &Connection, oid: Oid, text: &[u8]) -> Result<()> {
+    if looks_binary(text) {
+        return Ok(());
+    }
+
+    conn.execute(
+        "INSERT OR IGNORE INTO blob_fts_oid (oid) VALUES (?)",
+        (oid.as_bytes(),),
+    )?;
+    let rowid: i64 = conn.query_row(
+        "SELECT rowid FROM blob_fts_oid WHERE oid = ?",
+        (oid.as_bytes(),),
+        |row| row.get(0),
+    )?;
+    conn.execute(
+        "INSERT OR IGNORE INTO blob_fts (rowid, content) VALUES (?, ?)",
+        (rowid, String::from_utf8_lossy(text).as_ref()),
+    )?;
+
+    Ok(())
+}
+
+/// Git's own heuristic for "is this blob text": no NUL byte in the first
+/// 8000 bytes.
+pub fn looks_binary(content: &[u8]) -> bool {
+    content.iter().take(8000).any(|&b| b == 0)
+}
+
+/// Stream every row of `commits`, in no particular order, to `f`. Used by
+/// [`crate::convert`] to copy a store into a different backend.
+pub fn for_each_commit(conn: &Connection, mut f: impl FnMut(CommitRecord) -> Result<()>) -> Result<()> {
+    let mut stmt = conn.prepare(
+        "SELECT oid, tree_oid, message, parents, author_name, author_email, author_date,
+                committer_name, committer_email, committer_date
+         FROM commits",
+    )?;
+    let mut rows = stmt.query([])?;
+    while let Some(row) = rows.next()? {
+        let oid: [u8; 20] = row.get(0)?;
+        let tree_oid: [u8; 20] = row.get(1)?;
+        f(CommitRecord {
+            oid: Oid::from_bytes(&oid)?,
+            tree_oid: Oid::from_bytes(&tree_oid)?,
+            message: row.get(2)?,
+            parents: row.get(3)?,
+            author_name: row.get(4)?,
+            author_email: row.get(5)?,
+            author_date: row.get(6)?,
+            committer_name: row.get(7)?,
+            committer_email: row.get(8)?,
+            committer_date: row.get(9)?,
+        })?;
+    }
+    Ok(())
+}
+
+/// Stream every row of `tree_entries`, in no particular order, to `f`.
+pub fn for_each_tree_entry(
+    conn: &Connection,
+    mut f: impl FnMut(TreeEntryRecord) -> Result<()>,
+) -> Result<()> {
+    let mut stmt = conn.prepare("SELECT tree_oid, name, kind, oid, mode FROM tree_entries")?;
+    let mut rows = stmt.query([])?;
+    while let Some(row) = rows.next()? {
+        let tree_oid: [u8; 20] = row.get(0)?;
+        let kind: u8 = row.get(2)?;
+        let oid: [u8; 20] = row.get(3)?;
+        f(TreeEntryRecord {
+            tree_oid: Oid::from_bytes(&tree_oid)?,
+            name: row.get(1)?,
+            kind: object_type_from_int(kind),
+            oid: Oid::from_bytes(&oid)?,
+            mode: row.get(4)?,
+        })?;
+    }
+    Ok(())
+}
+
+/// Stream every `(oid, content_lz4)` row of `blobs` to `f`, without
+/// decompressing — backends store the same lz4 payload.
+pub fn for_each_blob(conn: &Connection, mut f: impl FnMut(Oid, Vec<u8>) -> Result<()>) -> Result<()> {
+    let mut stmt = conn.prepare("SELECT oid, content_lz4 FROM blobs")?;
+    let mut rows = stmt.query([])?;
+    while let Some(row) = rows.next()? {
+        let oid: [u8; 20] = row.get(0)?;
+        f(Oid::from_bytes(&oid)?, row.get(1)?)?;
+    }
+    Ok(())
+}
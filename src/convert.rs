@@ -0,0 +1,62 @@
+use std::path::PathBuf;
+
+use anyhow::Result;
+use clap::ValueEnum;
+use heed::EnvOpenOptions;
+use rusqlite::Connection;
+
+use crate::backend::{self, Backend, LmdbBackend, SqliteBackend};
+use crate::migrations;
+
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BackendKind {
+    Sqlite,
+    Lmdb,
+}
+
+/// Stream every commit, tree entry and blob from the store at `from` into a
+/// fresh store at `to`, translating between backends as it goes. Blob
+/// content is copied as-is (both backends store the same lz4 payload), so
+/// no decompression happens on the hot path.
+pub fn convert(from: BackendKind, from_path: PathBuf, to: BackendKind, to_path: PathBuf) -> Result<()> {
+    match to {
+        BackendKind::Sqlite => {
+            let mut conn = Connection::open(to_path)?;
+            migrations().to_latest(&mut conn)?;
+
+            let tx = conn.transaction()?;
+            {
+                let mut dest = SqliteBackend::new(&tx)?;
+                copy_all(from, from_path, &mut dest)?;
+            }
+            tx.commit()?;
+        }
+        BackendKind::Lmdb => {
+            std::fs::create_dir_all(&to_path)?;
+            let env = unsafe { EnvOpenOptions::new().map_size(1 << 40).max_dbs(8).open(&to_path)? };
+            let mut dest = LmdbBackend::new(&env)?;
+            copy_all(from, from_path, &mut dest)?;
+            dest.commit()?;
+        }
+    }
+
+    Ok(())
+}
+
+fn copy_all(from: BackendKind, from_path: PathBuf, dest: &mut impl Backend) -> Result<()> {
+    match from {
+        BackendKind::Sqlite => {
+            let conn = Connection::open(from_path)?;
+            backend::sqlite::for_each_commit(&conn, |c| dest.put_commit(&c))?;
+            backend::sqlite::for_each_tree_entry(&conn, |e| dest.put_tree_entry(&e).map(|_| ()))?;
+            backend::sqlite::for_each_blob(&conn, |oid, content| dest.put_blob(oid, &content))?;
+        }
+        BackendKind::Lmdb => {
+            let env = unsafe { EnvOpenOptions::new().map_size(1 << 40).max_dbs(8).open(&from_path)? };
+            backend::lmdb::for_each_commit(&env, |c| dest.put_commit(&c))?;
+            backend::lmdb::for_each_tree_entry(&env, |e| dest.put_tree_entry(&e).map(|_| ()))?;
+            backend::lmdb::for_each_blob(&env, |oid, content| dest.put_blob(oid, &content))?;
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,442 @@
+use std::collections::BTreeMap;
+use std::io::Read as _;
+use std::path::{Path, PathBuf};
+
+use anyhow::Result;
+use git2::{ObjectType, Oid};
+use lz4::Decoder;
+use rusqlite::Connection;
+
+/// Number of unchanged lines kept around a change for context, matching
+/// the default of `diff -u`.
+const CONTEXT_LINES: usize = 3;
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum DiffStatus {
+    Added,
+    Removed,
+    Modified,
+}
+
+#[derive(Debug, Clone)]
+pub enum DiffLine {
+    Context(String),
+    Added(String),
+    Removed(String),
+}
+
+#[derive(Debug, Clone)]
+pub struct Hunk {
+    pub old_start: usize,
+    pub old_lines: usize,
+    pub new_start: usize,
+    pub new_lines: usize,
+    pub lines: Vec<DiffLine>,
+}
+
+#[derive(Debug, Clone)]
+pub struct FileDiff {
+    pub path: PathBuf,
+    pub status: DiffStatus,
+    pub hunks: Vec<Hunk>,
+}
+
+/// Diff two commits purely from the ingested tables, without needing the
+/// original git repo.
+pub fn diff(conn: &Connection, old_commit_oid: Oid, new_commit_oid: Oid) -> Result<Vec<FileDiff>> {
+    let old_tree = commit_tree_oid(conn, old_commit_oid)?;
+    let new_tree = commit_tree_oid(conn, new_commit_oid)?;
+
+    let mut out = Vec::new();
+    diff_trees(conn, Path::new(""), Some(old_tree), Some(new_tree), &mut out)?;
+    Ok(out)
+}
+
+fn commit_tree_oid(conn: &Connection, commit_oid: Oid) -> Result<Oid> {
+    let tree_oid: [u8; 20] = conn.query_row(
+        "SELECT tree_oid FROM commits WHERE oid = ?",
+        (commit_oid.as_bytes(),),
+        |row| row.get(0),
+    )?;
+    Ok(Oid::from_bytes(&tree_oid)?)
+}
+
+fn tree_entries(conn: &Connection, tree_oid: Oid) -> Result<BTreeMap<Vec<u8>, (u8, Oid)>> {
+    let mut stmt = conn.prepare("SELECT name, kind, oid FROM tree_entries WHERE tree_oid = ?")?;
+    let rows = stmt.query_map((tree_oid.as_bytes(),), |row| {
+        let name: Vec<u8> = row.get(0)?;
+        let kind: u8 = row.get(1)?;
+        let oid: [u8; 20] = row.get(2)?;
+        Ok((name, kind, oid))
+    })?;
+
+    let mut entries = BTreeMap::new();
+    for row in rows {
+        let (name, kind, oid) = row?;
+        entries.insert(name, (kind, Oid::from_bytes(&oid)?));
+    }
+    Ok(entries)
+}
+
+fn diff_trees(
+    conn: &Connection,
+    prefix: &Path,
+    old_tree: Option<Oid>,
+    new_tree: Option<Oid>,
+    out: &mut Vec<FileDiff>,
+) -> Result<()> {
+    let old_entries = old_tree.map(|t| tree_entries(conn, t)).transpose()?.unwrap_or_default();
+    let new_entries = new_tree.map(|t| tree_entries(conn, t)).transpose()?.unwrap_or_default();
+
+    let names: std::collections::BTreeSet<&Vec<u8>> =
+        old_entries.keys().chain(new_entries.keys()).collect();
+
+    for name in names {
+        let path = prefix.join(String::from_utf8_lossy(name).as_ref());
+        let old = old_entries.get(name).copied();
+        let new = new_entries.get(name).copied();
+
+        match (old, new) {
+            (Some((_, old_oid)), Some((_, new_oid))) if old_oid == new_oid => {}
+            (Some((old_kind, old_oid)), Some((new_kind, new_oid)))
+                if old_kind == kind_int(ObjectType::Tree) && new_kind == kind_int(ObjectType::Tree) =>
+            {
+                diff_trees(conn, &path, Some(old_oid), Some(new_oid), out)?;
+            }
+            (Some((old_kind, old_oid)), Some((new_kind, new_oid)))
+                if old_kind == kind_int(ObjectType::Blob) && new_kind == kind_int(ObjectType::Blob) =>
+            {
+                let old_content = read_blob(conn, old_oid)?;
+                let new_content = read_blob(conn, new_oid)?;
+                let hunks = diff_lines(&old_content, &new_content);
+                if !hunks.is_empty() {
+                    out.push(FileDiff {
+                        path,
+                        status: DiffStatus::Modified,
+                        hunks,
+                    });
+                }
+            }
+            (Some((old_kind, old_oid)), new_entry) => {
+                // Either removed outright, or the path changed kind (e.g.
+                // file -> directory): treat the old side as fully removed.
+                remove_subtree(conn, &path, old_kind, old_oid, out)?;
+                if let Some((new_kind, new_oid)) = new_entry {
+                    add_subtree(conn, &path, new_kind, new_oid, out)?;
+                }
+            }
+            (None, Some((new_kind, new_oid))) => {
+                add_subtree(conn, &path, new_kind, new_oid, out)?;
+            }
+            (None, None) => unreachable!(),
+        }
+    }
+
+    Ok(())
+}
+
+fn add_subtree(conn: &Connection, path: &Path, kind: u8, oid: Oid, out: &mut Vec<FileDiff>) -> Result<()> {
+    if kind == kind_int(ObjectType::Tree) {
+        diff_trees(conn, path, None, Some(oid), out)
+    } else {
+        let content = read_blob(conn, oid)?;
+        out.push(FileDiff {
+            path: path.to_path_buf(),
+            status: DiffStatus::Added,
+            hunks: diff_lines(&[], &content),
+        });
+        Ok(())
+    }
+}
+
+fn remove_subtree(conn: &Connection, path: &Path, kind: u8, oid: Oid, out: &mut Vec<FileDiff>) -> Result<()> {
+    if kind == kind_int(ObjectType::Tree) {
+        diff_trees(conn, path, Some(oid), None, out)
+    } else {
+        let content = read_blob(conn, oid)?;
+        out.push(FileDiff {
+            path: path.to_path_buf(),
+            status: DiffStatus::Removed,
+            hunks: diff_lines(&content, &[]),
+        });
+        Ok(())
+    }
+}
+
+fn read_blob(conn: &Connection, oid: Oid) -> Result<Vec<u8>> {
+    let content_lz4: Vec<u8> = conn.query_row(
+        "SELECT content_lz4 FROM blobs WHERE oid = ?",
+        (oid.as_bytes(),),
+        |row| row.get(0),
+    )?;
+    let mut decoder = Decoder::new(content_lz4.as_slice())?;
+    let mut content = Vec::new();
+    decoder.read_to_end(&mut content)?;
+    Ok(content)
+}
+
+fn kind_int(kind: ObjectType) -> u8 {
+    match kind {
+        ObjectType::Any => 1,
+        ObjectType::Commit => 2,
+        ObjectType::Tree => 3,
+        ObjectType::Blob => 4,
+        ObjectType::Tag => 5,
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum EditOp {
+    Equal,
+    Delete,
+    Insert,
+}
+
+/// Myers O(ND) line diff, producing the edit script that turns `old` into
+/// `new`.
+fn myers(old: &[&str], new: &[&str]) -> Vec<EditOp> {
+    let n = old.len();
+    let m = new.len();
+    let max = n + m;
+    if max == 0 {
+        return Vec::new();
+    }
+
+    let offset = max as isize;
+    let size = 2 * max + 1;
+    let idx = |k: isize| (k + offset) as usize;
+
+    let mut trace: Vec<Vec<isize>> = Vec::new();
+    let mut v = vec![0isize; size];
+
+    'outer: for d in 0..=max as isize {
+        for k in (-d..=d).step_by(2) {
+            let mut x = if k == -d || (k != d && v[idx(k - 1)] < v[idx(k + 1)]) {
+                v[idx(k + 1)]
+            } else {
+                v[idx(k - 1)] + 1
+            };
+            let mut y = x - k;
+
+            while (x as usize) < n && (y as usize) < m && old[x as usize] == new[y as usize] {
+                x += 1;
+                y += 1;
+            }
+            v[idx(k)] = x;
+
+            if x as usize >= n && y as usize >= m {
+                trace.push(v.clone());
+                break 'outer;
+            }
+        }
+        trace.push(v.clone());
+    }
+
+    // Backtrack through the trace to recover the edit script.
+    let mut ops = Vec::new();
+    let mut x = n as isize;
+    let mut y = m as isize;
+    for d in (0..trace.len()).rev() {
+        let v = &trace[d];
+        let k = x - y;
+        let prev_k = if k == -(d as isize) || (k != d as isize && v[idx(k - 1)] < v[idx(k + 1)]) {
+            k + 1
+        } else {
+            k - 1
+        };
+        let prev_x = if d == 0 { 0 } else { trace[d - 1][idx(prev_k)] };
+        let prev_y = prev_x - prev_k;
+
+        while x > prev_x && y > prev_y {
+            ops.push(EditOp::Equal);
+            x -= 1;
+            y -= 1;
+        }
+
+        if d > 0 {
+            if x == prev_x {
+                ops.push(EditOp::Insert);
+                y -= 1;
+            } else {
+                ops.push(EditOp::Delete);
+                x -= 1;
+            }
+        }
+    }
+    ops.reverse();
+    ops
+}
+
+fn diff_lines(old: &[u8], new: &[u8]) -> Vec<Hunk> {
+    let old_text = String::from_utf8_lossy(old);
+    let new_text = String::from_utf8_lossy(new);
+    let old_lines: Vec<&str> = split_lines(&old_text);
+    let new_lines: Vec<&str> = split_lines(&new_text);
+
+    let ops = myers(&old_lines, &new_lines);
+
+    // Walk the edit script, tracking 1-based positions in both files, and
+    // group changes (plus CONTEXT_LINES of surrounding equal lines) into
+    // hunks the same way `diff -u` does.
+    struct Entry {
+        op: EditOp,
+        old_line: Option<usize>,
+        new_line: Option<usize>,
+    }
+    let mut entries = Vec::with_capacity(ops.len());
+    let (mut oi, mut ni) = (0usize, 0usize);
+    for op in &ops {
+        match op {
+            EditOp::Equal => {
+                oi += 1;
+                ni += 1;
+                entries.push(Entry { op: *op, old_line: Some(oi), new_line: Some(ni) });
+            }
+            EditOp::Delete => {
+                oi += 1;
+                entries.push(Entry { op: *op, old_line: Some(oi), new_line: None });
+            }
+            EditOp::Insert => {
+                ni += 1;
+                entries.push(Entry { op: *op, old_line: None, new_line: Some(ni) });
+            }
+        }
+    }
+
+    let mut hunks = Vec::new();
+    let mut i = 0;
+    while i < entries.len() {
+        if entries[i].op == EditOp::Equal {
+            i += 1;
+            continue;
+        }
+
+        // Start of a changed region; grab leading context.
+        let start = i.saturating_sub(CONTEXT_LINES);
+        let mut end = i;
+        while end < entries.len() {
+            if entries[end].op != EditOp::Equal {
+                end += 1;
+                continue;
+            }
+            // Look ahead: if another change starts within 2*CONTEXT of
+            // here, keep this as one hunk; otherwise close it out.
+            let mut run = end;
+            while run < entries.len() && entries[run].op == EditOp::Equal {
+                run += 1;
+            }
+            if run < entries.len() && run - end <= 2 * CONTEXT_LINES {
+                end = run + 1;
+                continue;
+            }
+            end = (end + CONTEXT_LINES).min(entries.len());
+            break;
+        }
+
+        let lines: Vec<DiffLine> = entries[start..end]
+            .iter()
+            .map(|e| match e.op {
+                EditOp::Equal => DiffLine::Context(old_lines[e.old_line.unwrap() - 1].to_string()),
+                EditOp::Delete => DiffLine::Removed(old_lines[e.old_line.unwrap() - 1].to_string()),
+                EditOp::Insert => DiffLine::Added(new_lines[e.new_line.unwrap() - 1].to_string()),
+            })
+            .collect();
+
+        // If this hunk has no line on one side at all (a whole added/removed
+        // file has no Equal entries to anchor to), the conventional start
+        // for that side is the line preceding the hunk: 0 at BOF, or
+        // whatever line immediately precedes it otherwise.
+        let old_start = entries[start..end].iter().find_map(|e| e.old_line).unwrap_or_else(|| {
+            if start == 0 {
+                0
+            } else {
+                entries[start - 1].old_line.unwrap_or(0)
+            }
+        });
+        let new_start = entries[start..end].iter().find_map(|e| e.new_line).unwrap_or_else(|| {
+            if start == 0 {
+                0
+            } else {
+                entries[start - 1].new_line.unwrap_or(0)
+            }
+        });
+        let old_lines_count = lines.iter().filter(|l| !matches!(l, DiffLine::Added(_))).count();
+        let new_lines_count = lines.iter().filter(|l| !matches!(l, DiffLine::Removed(_))).count();
+
+        hunks.push(Hunk {
+            old_start,
+            old_lines: old_lines_count,
+            new_start,
+            new_lines: new_lines_count,
+            lines,
+        });
+
+        i = end;
+    }
+
+    hunks
+}
+
+fn split_lines(text: &str) -> Vec<&str> {
+    if text.is_empty() {
+        return Vec::new();
+    }
+    text.lines().collect()
+}
+
+impl Hunk {
+    /// Render the `@@ -a,b +c,d @@` header for this hunk.
+    pub fn header(&self) -> String {
+        format!(
+            "@@ -{},{} +{},{} @@",
+            self.old_start, self.old_lines, self.new_start, self.new_lines
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn headers(old: &[u8], new: &[u8]) -> Vec<String> {
+        diff_lines(old, new).iter().map(Hunk::header).collect()
+    }
+
+    #[test]
+    fn whole_file_added_starts_at_zero() {
+        assert_eq!(headers(b"", b"a\nb\n"), vec!["@@ -0,0 +1,2 @@"]);
+    }
+
+    #[test]
+    fn whole_file_removed_starts_at_zero() {
+        assert_eq!(headers(b"a\nb\n", b""), vec!["@@ -1,2 +0,0 @@"]);
+    }
+
+    #[test]
+    fn single_line_change_keeps_context() {
+        let old = b"a\nb\nc\nd\ne\n";
+        let new = b"a\nb\nX\nd\ne\n";
+        let hunks = diff_lines(old, new);
+        assert_eq!(hunks.len(), 1);
+        assert_eq!(hunks[0].header(), "@@ -1,5 +1,5 @@");
+    }
+
+    #[test]
+    fn identical_files_produce_no_hunks() {
+        assert!(diff_lines(b"a\nb\nc\n", b"a\nb\nc\n").is_empty());
+    }
+
+    #[test]
+    fn myers_edit_script_for_pure_insert() {
+        let old: Vec<&str> = Vec::new();
+        let new = vec!["a", "b"];
+        assert_eq!(myers(&old, &new), vec![EditOp::Insert, EditOp::Insert]);
+    }
+
+    #[test]
+    fn myers_edit_script_for_pure_delete() {
+        let old = vec!["a", "b"];
+        let new: Vec<&str> = Vec::new();
+        assert_eq!(myers(&old, &new), vec![EditOp::Delete, EditOp::Delete]);
+    }
+}
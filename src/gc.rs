@@ -0,0 +1,312 @@
+use std::collections::{HashSet, VecDeque};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use anyhow::Result;
+use git2::Oid;
+use rusqlite::Connection;
+
+/// Counts of rows removed by a [`run`] sweep.
+#[derive(Debug, Default)]
+pub struct GcStats {
+    pub commits_deleted: usize,
+    pub tree_entries_deleted: usize,
+    pub blobs_deleted: usize,
+}
+
+/// Mark-and-sweep garbage collection over the store.
+///
+/// `commits`, `tree_entries` and `blobs` aren't partitioned by repo (only
+/// `direct_refs`/`ingest_progress` carry a `repo_id`), so a sweep has to
+/// seed reachability from *every* repo's refs, not just one — otherwise
+/// it would delete history that's only reachable from some other repo
+/// sharing this database. Every commit reachable from any repo's
+/// `direct_refs` (and, if `keep_newer` is set, every commit committed
+/// within that window of "now") is marked reachable, along with every tree
+/// and blob reachable from those commits. Anything left unmarked is
+/// deleted. The whole sweep runs in one transaction.
+pub fn run(conn: &mut Connection, keep_newer: Option<Duration>) -> Result<GcStats> {
+    let tx = conn.transaction()?;
+
+    let mut seeds: Vec<Oid> = Vec::new();
+    {
+        let mut stmt = tx.prepare("SELECT target_oid FROM direct_refs")?;
+        let mut rows = stmt.query([])?;
+        while let Some(row) = rows.next()? {
+            let oid: [u8; 20] = row.get(0)?;
+            seeds.push(Oid::from_bytes(&oid)?);
+        }
+    }
+
+    if let Some(keep_newer) = keep_newer {
+        let cutoff = SystemTime::now()
+            .duration_since(UNIX_EPOCH)?
+            .saturating_sub(keep_newer)
+            .as_secs() as i64;
+        let mut stmt = tx.prepare("SELECT oid FROM commits WHERE committer_date >= ?")?;
+        let mut rows = stmt.query((cutoff,))?;
+        while let Some(row) = rows.next()? {
+            let oid: [u8; 20] = row.get(0)?;
+            seeds.push(Oid::from_bytes(&oid)?);
+        }
+    }
+
+    let mut reachable_commits: HashSet<Oid> = HashSet::new();
+    let mut reachable: HashSet<Oid> = HashSet::new();
+
+    let mut commit_stmt = tx.prepare("SELECT tree_oid, parents FROM commits WHERE oid = ?")?;
+    let mut tree_entry_stmt =
+        tx.prepare("SELECT kind, oid FROM tree_entries WHERE tree_oid = ?")?;
+
+    let mut queue: VecDeque<Oid> = seeds.into_iter().collect();
+    while let Some(commit_oid) = queue.pop_front() {
+        if !reachable_commits.insert(commit_oid) {
+            continue;
+        }
+
+        let (tree_oid, parents): ([u8; 20], Vec<u8>) =
+            match commit_stmt.query_row((commit_oid.as_bytes(),), |row| {
+                Ok((row.get(0)?, row.get(1)?))
+            }) {
+                Ok(row) => row,
+                // A ref or keep-newer window can point at a commit that's
+                // already gone; nothing more to mark from it.
+                Err(rusqlite::Error::QueryReturnedNoRows) => continue,
+                Err(e) => return Err(e.into()),
+            };
+
+        mark_tree(&mut tree_entry_stmt, &mut reachable, Oid::from_bytes(&tree_oid)?)?;
+
+        for chunk in parents.chunks_exact(20) {
+            queue.push_back(Oid::from_bytes(chunk)?);
+        }
+    }
+    drop(commit_stmt);
+    drop(tree_entry_stmt);
+
+    tx.execute(
+        "CREATE TEMPORARY TABLE reachable_commits (oid BLOB NOT NULL PRIMARY KEY)",
+        [],
+    )?;
+    tx.execute(
+        "CREATE TEMPORARY TABLE reachable (oid BLOB NOT NULL PRIMARY KEY)",
+        [],
+    )?;
+    {
+        let mut insert_commit = tx.prepare("INSERT INTO reachable_commits (oid) VALUES (?)")?;
+        for oid in &reachable_commits {
+            insert_commit.execute((oid.as_bytes(),))?;
+        }
+        let mut insert_reachable = tx.prepare("INSERT INTO reachable (oid) VALUES (?)")?;
+        for oid in &reachable {
+            insert_reachable.execute((oid.as_bytes(),))?;
+        }
+    }
+
+    let tree_entries_deleted = tx.execute(
+        "DELETE FROM tree_entries WHERE tree_oid NOT IN (SELECT oid FROM reachable)",
+        [],
+    )?;
+    let blobs_deleted = tx.execute(
+        "DELETE FROM blobs WHERE oid NOT IN (SELECT oid FROM reachable)",
+        [],
+    )?;
+    let commits_deleted = tx.execute(
+        "DELETE FROM commits WHERE oid NOT IN (SELECT oid FROM reachable_commits)",
+        [],
+    )?;
+
+    tx.execute("DROP TABLE reachable_commits", [])?;
+    tx.execute("DROP TABLE reachable", [])?;
+
+    tx.commit()?;
+
+    Ok(GcStats {
+        commits_deleted,
+        tree_entries_deleted,
+        blobs_deleted,
+    })
+}
+
+fn mark_tree(
+    tree_entry_stmt: &mut rusqlite::Statement,
+    reachable: &mut HashSet<Oid>,
+    tree_oid: Oid,
+) -> Result<()> {
+    if !reachable.insert(tree_oid) {
+        return Ok(());
+    }
+
+    let entries = tree_entry_stmt.query_map((tree_oid.as_bytes(),), |row| {
+        let kind: u8 = row.get(0)?;
+        let oid: [u8; 20] = row.get(1)?;
+        Ok((kind, oid))
+    })?;
+
+    let mut children = Vec::new();
+    for entry in entries {
+        let (kind, oid) = entry?;
+        children.push((kind, Oid::from_bytes(&oid)?));
+    }
+
+    for (kind, oid) in children {
+        match kind {
+            // Tree
+            3 => mark_tree(tree_entry_stmt, reachable, oid)?,
+            // Blob
+            4 => {
+                reachable.insert(oid);
+            }
+            _ => {}
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn setup() -> Connection {
+        let conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch(
+            "CREATE TABLE direct_refs (repo_id INTEGER NOT NULL, name TEXT NOT NULL, target_oid BLOB NOT NULL);
+             CREATE TABLE commits (
+                oid BLOB NOT NULL PRIMARY KEY,
+                tree_oid BLOB NOT NULL,
+                message BLOB NOT NULL,
+                parents BLOB NOT NULL,
+                author_name BLOB NOT NULL,
+                author_email BLOB NOT NULL,
+                author_date INTEGER NOT NULL,
+                committer_name BLOB NOT NULL,
+                committer_email BLOB NOT NULL,
+                committer_date INTEGER NOT NULL
+             );
+             CREATE TABLE tree_entries (tree_oid BLOB NOT NULL, name BLOB NOT NULL, kind INTEGER NOT NULL, oid BLOB NOT NULL);
+             CREATE TABLE blobs (oid BLOB NOT NULL PRIMARY KEY, content_lz4 BLOB NOT NULL);",
+        )
+        .unwrap();
+        conn
+    }
+
+    fn oid(byte: u8) -> Oid {
+        Oid::from_bytes(&[byte; 20]).unwrap()
+    }
+
+    fn insert_commit(conn: &Connection, commit_oid: Oid, tree_oid: Oid, parents: &[Oid]) {
+        let parents: Vec<u8> = parents.iter().flat_map(|p| p.as_bytes().to_vec()).collect();
+        conn.execute(
+            "INSERT INTO commits (oid, tree_oid, message, parents, author_name, author_email, author_date, committer_name, committer_email, committer_date)
+             VALUES (?, ?, '', ?, '', '', 0, '', '', 0)",
+            (commit_oid.as_bytes(), tree_oid.as_bytes(), parents),
+        )
+        .unwrap();
+    }
+
+    fn insert_tree_entry(conn: &Connection, tree_oid: Oid, name: &str, kind: u8, child_oid: Oid) {
+        conn.execute(
+            "INSERT INTO tree_entries (tree_oid, name, kind, oid) VALUES (?, ?, ?, ?)",
+            (tree_oid.as_bytes(), name, kind, child_oid.as_bytes()),
+        )
+        .unwrap();
+    }
+
+    fn insert_blob(conn: &Connection, blob_oid: Oid) {
+        conn.execute(
+            "INSERT INTO blobs (oid, content_lz4) VALUES (?, '')",
+            (blob_oid.as_bytes(),),
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn sweeps_everything_unreachable_from_direct_refs() {
+        let mut conn = setup();
+
+        // Reachable: commit 1 -> tree 10 -> blob 20.
+        insert_commit(&conn, oid(1), oid(10), &[]);
+        insert_tree_entry(&conn, oid(10), "a.txt", 4, oid(20));
+        insert_blob(&conn, oid(20));
+        conn.execute(
+            "INSERT INTO direct_refs (repo_id, name, target_oid) VALUES (1, 'refs/heads/main', ?)",
+            (oid(1).as_bytes(),),
+        )
+        .unwrap();
+
+        // Unreachable: commit 2 -> tree 11 -> blob 21, no ref points at it.
+        insert_commit(&conn, oid(2), oid(11), &[]);
+        insert_tree_entry(&conn, oid(11), "b.txt", 4, oid(21));
+        insert_blob(&conn, oid(21));
+
+        let stats = run(&mut conn, None).unwrap();
+
+        assert_eq!(stats.commits_deleted, 1);
+        assert_eq!(stats.tree_entries_deleted, 1);
+        assert_eq!(stats.blobs_deleted, 1);
+
+        let remaining_commits: i64 = conn.query_row("SELECT COUNT(*) FROM commits", [], |r| r.get(0)).unwrap();
+        assert_eq!(remaining_commits, 1);
+        let remaining_blobs: Vec<u8> = conn
+            .query_row("SELECT oid FROM blobs", [], |r| r.get(0))
+            .unwrap();
+        assert_eq!(remaining_blobs, oid(20).as_bytes());
+    }
+
+    #[test]
+    fn keeps_unreachable_commits_within_keep_newer_window() {
+        let mut conn = setup();
+
+        insert_commit(&conn, oid(1), oid(10), &[]);
+        insert_tree_entry(&conn, oid(10), "a.txt", 4, oid(20));
+        insert_blob(&conn, oid(20));
+        conn.execute(
+            "UPDATE commits SET committer_date = ? WHERE oid = ?",
+            (
+                SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs() as i64,
+                oid(1).as_bytes(),
+            ),
+        )
+        .unwrap();
+
+        let stats = run(&mut conn, Some(Duration::from_secs(3600))).unwrap();
+
+        assert_eq!(stats.commits_deleted, 0);
+        assert_eq!(stats.blobs_deleted, 0);
+    }
+
+    #[test]
+    fn does_not_delete_history_reachable_only_from_another_repo() {
+        let mut conn = setup();
+
+        // Repo 1: commit 1 -> tree 10 -> blob 20.
+        insert_commit(&conn, oid(1), oid(10), &[]);
+        insert_tree_entry(&conn, oid(10), "a.txt", 4, oid(20));
+        insert_blob(&conn, oid(20));
+        conn.execute(
+            "INSERT INTO direct_refs (repo_id, name, target_oid) VALUES (1, 'refs/heads/main', ?)",
+            (oid(1).as_bytes(),),
+        )
+        .unwrap();
+
+        // Repo 2: commit 2 -> tree 11 -> blob 21, old enough to fall outside
+        // any keep_newer window, reachable only from repo 2's own ref.
+        insert_commit(&conn, oid(2), oid(11), &[]);
+        insert_tree_entry(&conn, oid(11), "b.txt", 4, oid(21));
+        insert_blob(&conn, oid(21));
+        conn.execute(
+            "INSERT INTO direct_refs (repo_id, name, target_oid) VALUES (2, 'refs/heads/main', ?)",
+            (oid(2).as_bytes(),),
+        )
+        .unwrap();
+
+        // A sweep with no repo_id to filter on must still mark both repos'
+        // refs, or repo 2's only-reachable-from-its-own-ref history would
+        // be deleted as a side effect of GCing repo 1.
+        let stats = run(&mut conn, None).unwrap();
+
+        assert_eq!(stats.commits_deleted, 0);
+        assert_eq!(stats.tree_entries_deleted, 0);
+        assert_eq!(stats.blobs_deleted, 0);
+    }
+}
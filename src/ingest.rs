@@ -1,57 +1,32 @@
 use anyhow::Result;
-use git2::{ObjectType, Oid, Repository, Tree};
+use git2::{ObjectType, Oid, Repository, Revwalk, Tree};
 use lz4::EncoderBuilder;
-use rusqlite::{Connection, Row, Statement, Transaction};
+use rusqlite::Connection;
 use std::io::Write;
 
-pub struct Ingestor<'tx, 'repo> {
+use crate::backend::{Backend, CommitRecord, SqliteBackend, TreeEntryRecord};
+
+pub struct Ingestor<'repo, B: Backend> {
     repo: &'repo Repository,
-    tree_entry_stmt: Statement<'tx>,
-    commit_stmt: Statement<'tx>,
-    blob_exists_stmt: Statement<'tx>,
-    blob_insert_stmt: Statement<'tx>,
+    backend: B,
     buf: Vec<u8>,
+    index_text: bool,
 }
 
-impl<'tx, 'repo> Ingestor<'tx, 'repo> {
-    pub fn new(repo: &'repo Repository, tx: &'tx Transaction<'tx>) -> Result<Self> {
-        let tree_entry_stmt = tx.prepare(
-            "INSERT OR IGNORE INTO tree_entries (
-                tree_oid, 
-                name, 
-                kind, 
-                oid
-            ) VALUES (?, ?, ?, ?)
-            RETURNING *",
-        )?;
-        let commit_stmt = tx.prepare(
-            "INSERT OR IGNORE INTO commits (
-                oid, 
-                tree_oid, 
-                message, 
-                parents,
-                author_name,
-                author_email,
-                author_date,
-                committer_name,
-                committer_email,
-                committer_date
-            ) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?)",
-        )?;
-        let blob_exists_stmt = tx.prepare("SELECT EXISTS (SELECT * FROM blobs WHERE oid = ?);")?;
-        let blob_insert_stmt = tx.prepare(
-            "INSERT OR IGNORE INTO blobs (oid, content_lz4) 
-            VALUES (?, ?)",
-        )?;
-
-        Ok(Self {
+impl<'repo, B: Backend> Ingestor<'repo, B> {
+    pub fn new(repo: &'repo Repository, backend: B) -> Self {
+        Self {
             repo,
-            tree_entry_stmt,
-            commit_stmt,
-            blob_exists_stmt,
-            blob_insert_stmt,
+            backend,
             buf: Vec::new(),
-        })
+            index_text: false,
+        }
+    }
+
+    /// Maintain the full-text search index as blobs are ingested.
+    pub fn index_text(mut self, index_text: bool) -> Self {
+        self.index_text = index_text;
+        self
     }
 
     pub fn add_commit(&mut self, commit_oid: Oid) -> Result<()> {
@@ -59,64 +34,67 @@ impl<'tx, 'repo> Ingestor<'tx, 'repo> {
         let commit = self.repo.find_commit(commit_oid)?;
 
         self.add_tree(&commit.tree()?)?;
+
         let mut parents: Vec<u8> = Vec::new();
         for parent in commit.parent_ids() {
             parents.extend(parent.as_bytes())
         }
 
-        self.commit_stmt.execute((
-            commit.id().as_bytes(),
-            commit.tree_id().as_bytes(),
-            commit.message_bytes(),
+        self.backend.put_commit(&CommitRecord {
+            oid: commit.id(),
+            tree_oid: commit.tree_id(),
+            message: commit.message_bytes().to_vec(),
             parents,
-            commit.author().name_bytes(),
-            commit.author().email_bytes(),
-            commit.author().when().seconds(),
-            commit.committer().name_bytes(),
-            commit.committer().email_bytes(),
-            commit.committer().when().seconds(),
-        ))?;
+            author_name: commit.author().name_bytes().to_vec(),
+            author_email: commit.author().email_bytes().to_vec(),
+            author_date: commit.author().when().seconds(),
+            committer_name: commit.committer().name_bytes().to_vec(),
+            committer_email: commit.committer().email_bytes().to_vec(),
+            committer_date: commit.committer().when().seconds(),
+        })?;
 
         Ok(())
     }
 
     fn add_tree(&mut self, tree: &Tree) -> Result<()> {
         for tree_obj in tree.into_iter() {
-            let new_entries = self.tree_entry_stmt.query_map(
-                (
-                    tree.id().as_bytes(),
-                    tree_obj.name_bytes(),
-                    object_type_to_int(tree_obj.kind()),
-                    tree_obj.id().as_bytes(),
-                ),
-                |row| TreeEntry::try_from(row),
-            )?;
-
-            let mut new: Option<TreeEntry> = None;
-            for new_entry in new_entries {
-                new = Some(new_entry?);
+            let inserted = self.backend.put_tree_entry(&TreeEntryRecord {
+                tree_oid: tree.id(),
+                name: tree_obj.name_bytes().to_vec(),
+                kind: tree_obj.kind(),
+                oid: tree_obj.id(),
+                mode: tree_obj.filemode(),
+            })?;
+
+            // Mirrors the old `INSERT OR IGNORE ... RETURNING *` check: if
+            // this entry was already known, its subtree has already been
+            // walked by an earlier ingest.
+            if !inserted {
+                continue;
             }
 
-            if let Some(new_entry) = new {
-                if new_entry.kind == Some(ObjectType::Tree) {
-                    self.add_tree(&self.repo.find_tree(new_entry.oid)?)?;
-                } else if new_entry.kind == Some(ObjectType::Blob) {
-                    if !self.blob_exists(new_entry.oid)? {
-                        self.insert_blob_content(new_entry.oid)?;
+            match tree_obj.kind() {
+                Some(ObjectType::Tree) => {
+                    self.add_tree(&self.repo.find_tree(tree_obj.id())?)?;
+                }
+                Some(ObjectType::Blob) => {
+                    if !self.backend.blob_exists(tree_obj.id())? {
+                        self.insert_blob_content(tree_obj.id())?;
                     }
                 }
+                _ => {}
             }
         }
         Ok(())
     }
 
-    fn blob_exists(&mut self, oid: Oid) -> Result<bool> {
-        Ok(self
-            .blob_exists_stmt
-            .query_row((oid.as_bytes(),), |row| row.get::<_, bool>(0))?)
-    }
-
     fn insert_blob_content(&mut self, oid: Oid) -> Result<()> {
+        let blob = self.repo.find_blob(oid)?;
+
+        if self.index_text {
+            self.backend.index_text(oid, blob.content())?;
+        }
+
         let mut dst = std::mem::take(&mut self.buf);
         dst.clear();
         let mut enc = EncoderBuilder::new()
@@ -124,64 +102,139 @@ impl<'tx, 'repo> Ingestor<'tx, 'repo> {
             .level(10)
             .favor_dec_speed(true)
             .build(dst)?;
-        let blob = self.repo.find_blob(oid)?;
         enc.write_all(blob.content())?;
         let (dst, r) = enc.finish();
         r?;
-        self.blob_insert_stmt.execute((oid.as_bytes(), &dst))?;
+        self.backend.put_blob(oid, &dst)?;
         self.buf = dst;
         Ok(())
     }
 }
 
-fn object_type_to_int(kind: Option<ObjectType>) -> u8 {
-    match kind {
-        None => 0,
-        Some(ObjectType::Any) => 1,
-        Some(ObjectType::Commit) => 2,
-        Some(ObjectType::Tree) => 3,
-        Some(ObjectType::Blob) => 4,
-        Some(ObjectType::Tag) => 5,
-    }
+/// Default number of commits ingested per SQLite transaction by
+/// [`BatchIngestor`].
+const DEFAULT_BATCH_COMMITS: usize = 1000;
+
+/// Details of a batch that was just committed, handed to
+/// [`BatchIngestor`]'s `on_commit` hooks.
+pub struct BatchCommitted<'a> {
+    pub repo_id: u32,
+    pub ref_name: &'a str,
+    pub batch_commits: usize,
+    pub last_commit: Oid,
+}
+
+/// Drives ingestion of a single ref's commits into a SQLite store in
+/// bounded batches, instead of one giant transaction for the whole
+/// revwalk. Progress is recorded in `ingest_progress` after every batch, so
+/// a crash (or a deliberate restart) resumes from the last fully-ingested
+/// commit rather than re-walking from the root.
+pub struct BatchIngestor<'repo> {
+    repo: &'repo Repository,
+    repo_id: u32,
+    batch_commits: usize,
+    index_text: bool,
+    on_commit: Vec<Box<dyn FnMut(&BatchCommitted) -> Result<()>>>,
 }
 
-fn object_type_from_int(val: u8) -> Option<ObjectType> {
-    match val {
-        0 => None,
-        1 => Some(ObjectType::Any),
-        2 => Some(ObjectType::Commit),
-        3 => Some(ObjectType::Tree),
-        4 => Some(ObjectType::Blob),
-        5 => Some(ObjectType::Tag),
-        _ => panic!("unknown"),
+impl<'repo> BatchIngestor<'repo> {
+    pub fn new(repo: &'repo Repository, repo_id: u32) -> Self {
+        Self {
+            repo,
+            repo_id,
+            batch_commits: DEFAULT_BATCH_COMMITS,
+            index_text: false,
+            on_commit: Vec::new(),
+        }
+    }
+
+    pub fn batch_commits(mut self, batch_commits: usize) -> Self {
+        self.batch_commits = batch_commits;
+        self
+    }
+
+    /// Maintain the full-text search index as blobs are ingested.
+    pub fn index_text(mut self, index_text: bool) -> Self {
+        self.index_text = index_text;
+        self
+    }
+
+    pub fn on_commit(mut self, hook: impl FnMut(&BatchCommitted) -> Result<()> + 'static) -> Self {
+        self.on_commit.push(Box::new(hook));
+        self
+    }
+
+    /// Ingest everything `walker` yields for `ref_name`, resuming after
+    /// whatever commit `ingest_progress` last recorded for this ref.
+    pub fn run(&mut self, conn: &mut Connection, ref_name: &str, mut walker: Revwalk) -> Result<()> {
+        if let Some(last) = read_progress(conn, self.repo_id, ref_name)? {
+            walker.hide(last)?;
+        }
+
+        let mut batch = Vec::with_capacity(self.batch_commits);
+        for commit_oid in walker {
+            batch.push(commit_oid?);
+            if batch.len() >= self.batch_commits {
+                self.commit_batch(conn, ref_name, &mut batch)?;
+            }
+        }
+        if !batch.is_empty() {
+            self.commit_batch(conn, ref_name, &mut batch)?;
+        }
+
+        Ok(())
+    }
+
+    fn commit_batch(&mut self, conn: &mut Connection, ref_name: &str, batch: &mut Vec<Oid>) -> Result<()> {
+        let last_commit = *batch.last().expect("commit_batch called with an empty batch");
+
+        let tx = conn.transaction()?;
+        {
+            let backend = SqliteBackend::new(&tx)?;
+            let mut ingestor = Ingestor::new(self.repo, backend).index_text(self.index_text);
+            for &commit_oid in batch.iter() {
+                ingestor.add_commit(commit_oid)?;
+            }
+        }
+        write_progress(&tx, self.repo_id, ref_name, last_commit)?;
+        tx.commit()?;
+
+        // Keep the WAL from growing unbounded over a multi-million-object
+        // ingest now that we're committing incrementally.
+        conn.execute_batch("PRAGMA wal_checkpoint(TRUNCATE);")?;
+
+        let event = BatchCommitted {
+            repo_id: self.repo_id,
+            ref_name,
+            batch_commits: batch.len(),
+            last_commit,
+        };
+        for hook in &mut self.on_commit {
+            hook(&event)?;
+        }
+
+        batch.clear();
+        Ok(())
     }
 }
 
-#[derive(Debug)]
-struct TreeEntry {
-    tree_oid: Oid,
-    name: Vec<u8>,
-    kind: Option<ObjectType>,
-    oid: Oid,
+fn read_progress(conn: &Connection, repo_id: u32, ref_name: &str) -> Result<Option<Oid>> {
+    let oid: Option<[u8; 20]> = conn
+        .query_row(
+            "SELECT last_commit_oid FROM ingest_progress WHERE repo_id = ? AND ref_name = ?",
+            (repo_id, ref_name),
+            |row| row.get(0),
+        )
+        .ok();
+    Ok(oid.map(|oid| Oid::from_bytes(&oid)).transpose()?)
 }
 
-impl<'a> TryFrom<&Row<'a>> for TreeEntry {
-    type Error = rusqlite::Error;
-
-    fn try_from(row: &Row) -> Result<Self, Self::Error> {
-        let tree_oid: Oid = row
-            .get::<_, [u8; 20]>(0)
-            .map(|arr| Oid::from_bytes(arr.as_slice()).unwrap())?;
-        let name: Vec<u8> = row.get(1)?;
-        let kind = object_type_from_int(row.get(2)?);
-        let oid: Oid = row
-            .get::<_, [u8; 20]>(3)
-            .map(|arr| Oid::from_bytes(arr.as_slice()).unwrap())?;
-        Ok(TreeEntry {
-            tree_oid,
-            name,
-            kind,
-            oid,
-        })
-    }
+fn write_progress(conn: &Connection, repo_id: u32, ref_name: &str, last_commit: Oid) -> Result<()> {
+    conn.execute(
+        "INSERT INTO ingest_progress (repo_id, ref_name, last_commit_oid)
+         VALUES (?, ?, ?)
+         ON CONFLICT (repo_id, ref_name) DO UPDATE SET last_commit_oid = excluded.last_commit_oid",
+        (repo_id, ref_name, last_commit.as_bytes()),
+    )?;
+    Ok(())
 }
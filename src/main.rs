@@ -1,15 +1,39 @@
+mod archive;
+mod backend;
+mod convert;
+mod diff;
+mod gc;
+mod ingest;
+mod search;
+mod store;
+
+use std::fs::File;
+use std::io::Write as _;
 use std::path::PathBuf;
+use std::time::Duration;
 
 use anyhow::{Context, Error, Result};
+use archive::ArchiveFormat;
 use clap::{Parser, Subcommand};
-use git2::{ObjectType, Oid, ReferenceType, Repository, Sort, Tree};
-use lz4::EncoderBuilder;
+use convert::BackendKind;
+use git2::{Oid, ReferenceType, Repository, Sort};
 use rusqlite::{
     types::{FromSql, FromSqlResult, ToSql, ValueRef},
     Connection, Row,
 };
 use rusqlite_migration::{Migrations, M};
-use std::io::Write;
+
+/// The full set of migrations any command that touches the schema needs to
+/// apply. Kept as one list so `gc`/`ingest`/`index`/`convert` can't drift
+/// out of sync with each other the way `gc` previously did.
+pub(crate) fn migrations() -> Migrations<'static> {
+    Migrations::new(vec![
+        M::up(include_str!("migrations/1.up.sql")).down(include_str!("migrations/1.down.sql")),
+        M::up(include_str!("migrations/2.up.sql")).down(include_str!("migrations/2.down.sql")),
+        M::up(include_str!("migrations/3.up.sql")).down(include_str!("migrations/3.down.sql")),
+        M::up(include_str!("migrations/4.up.sql")).down(include_str!("migrations/4.down.sql")),
+    ])
+}
 
 #[derive(Parser, Debug)]
 pub struct Cli {
@@ -20,6 +44,14 @@ pub struct Cli {
 #[derive(Subcommand, Debug)]
 pub enum Command {
     Ingest(IngestArgs),
+    Gc(GcArgs),
+    Convert(ConvertArgs),
+    Archive(ArchiveArgs),
+    Index(IndexArgs),
+    Ls(LsArgs),
+    Cat(CatArgs),
+    Diff(DiffArgs),
+    Search(SearchArgs),
 }
 
 #[derive(Parser, Debug)]
@@ -32,211 +64,224 @@ pub struct IngestArgs {
     repo_name: String,
     #[clap(long = "repo-path")]
     repo_path: PathBuf,
+    /// Maintain the full-text search index as commits are ingested.
+    #[clap(long = "index")]
+    index: bool,
 }
 
-fn main() -> Result<()> {
-    let args = Cli::try_parse()?;
-    let args = match args.cmd {
-        Command::Ingest(a) => a,
-    };
+#[derive(Parser, Debug)]
+pub struct IndexArgs {
+    #[clap(long = "db")]
+    db: PathBuf,
+}
 
-    let mut conn = Connection::open(args.db)?;
-    conn.pragma_update_and_check(None, "journal_mode", "WAL", |_| Ok(()))?;
+#[derive(Parser, Debug)]
+pub struct GcArgs {
+    #[clap(long = "db")]
+    db: PathBuf,
+    /// Keep commits (and everything they reference) committed within this
+    /// many seconds of now, even if they're unreachable from any ref.
+    #[clap(long = "keep-newer")]
+    keep_newer: Option<u64>,
+}
 
-    let migrations = Migrations::new(vec![
-        M::up(include_str!("migrations/1.up.sql")).down(include_str!("migrations/1.down.sql"))
-    ]);
+#[derive(Parser, Debug)]
+pub struct ConvertArgs {
+    #[clap(long = "from-backend", value_enum)]
+    from_backend: BackendKind,
+    #[clap(long = "from")]
+    from: PathBuf,
+    #[clap(long = "to-backend", value_enum)]
+    to_backend: BackendKind,
+    #[clap(long = "to")]
+    to: PathBuf,
+}
 
-    migrations.to_latest(&mut conn).context("migrating")?;
+#[derive(Parser, Debug)]
+pub struct LsArgs {
+    #[clap(long = "db")]
+    db: PathBuf,
+    #[clap(long = "commit")]
+    commit: String,
+    /// Path of the directory to list within the commit's tree; defaults to
+    /// the root.
+    #[clap(long = "path", default_value = "")]
+    path: PathBuf,
+}
 
-    let repo = Repository::open(args.repo_path)?;
+#[derive(Parser, Debug)]
+pub struct CatArgs {
+    #[clap(long = "db")]
+    db: PathBuf,
+    #[clap(long = "commit")]
+    commit: String,
+    #[clap(long = "path")]
+    path: PathBuf,
+}
 
-    let changed_refs = compare_refs(&mut conn, args.repo_id, &repo, "refs/heads/*")?;
-    println!("{:?}", changed_refs);
+#[derive(Parser, Debug)]
+pub struct DiffArgs {
+    #[clap(long = "db")]
+    db: PathBuf,
+    #[clap(long = "old-commit")]
+    old_commit: String,
+    #[clap(long = "new-commit")]
+    new_commit: String,
+}
 
-    let mut walker = repo.revwalk()?;
-    walker.set_sorting(Sort::TOPOLOGICAL | Sort::REVERSE)?;
-    for diff in changed_refs {
-        if let Some(new) = diff.new_target {
-            walker.push(new)?;
-        }
-        if let Some(old) = diff.old_target {
-            walker.hide(old)?;
-        }
-    }
+#[derive(Parser, Debug)]
+pub struct SearchArgs {
+    #[clap(long = "db")]
+    db: PathBuf,
+    #[clap(long = "query")]
+    query: String,
+}
 
-    let tx = conn.transaction()?;
-    {
-        let mut tree_entry_stmt = tx.prepare(
-            "INSERT OR IGNORE INTO tree_entries (
-                tree_oid, 
-                name, 
-                kind, 
-                oid
-            ) VALUES (?, ?, ?, ?)
-            RETURNING *",
-        )?;
-        let mut commit_stmt = tx.prepare(
-            "INSERT OR IGNORE INTO commits (
-                oid, 
-                tree_oid, 
-                message, 
-                parents,
-                author_name,
-                author_email,
-                author_date,
-                committer_name,
-                committer_email,
-                committer_date
-            ) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?)",
-        )?;
-        let mut blob_exists_stmt =
-            tx.prepare("SELECT EXISTS (SELECT * FROM blobs WHERE oid = ?);")?;
-        let mut blob_insert_stmt = tx.prepare(
-            "INSERT OR IGNORE INTO blobs (oid, content_lz4) 
-            VALUES (?, ?)",
-        )?;
-        for (i, commit_oid) in walker.enumerate() {
-            let commit_oid = commit_oid?;
-            println!("{}, {}", i, commit_oid);
-            let commit = repo.find_commit(commit_oid)?;
-
-            insert_tree(
-                &mut tree_entry_stmt,
-                &mut blob_exists_stmt,
-                &mut blob_insert_stmt,
-                &repo,
-                &commit.tree()?,
-            )?;
-
-            let mut parents: Vec<u8> = Vec::new();
-            for parent in commit.parent_ids() {
-                parents.extend(parent.as_bytes())
-            }
+#[derive(Parser, Debug)]
+pub struct ArchiveArgs {
+    #[clap(long = "db")]
+    db: PathBuf,
+    #[clap(long = "commit")]
+    commit: String,
+    #[clap(long = "format", value_enum, default_value = "tar-gz")]
+    format: ArchiveFormat,
+    #[clap(long = "output")]
+    output: PathBuf,
+}
 
-            commit_stmt.execute((
-                commit.id().as_bytes(),
-                commit.tree_id().as_bytes(),
-                commit.message_bytes(),
-                parents,
-                commit.author().name_bytes(),
-                commit.author().email_bytes(),
-                commit.author().when().seconds(),
-                commit.committer().name_bytes(),
-                commit.committer().email_bytes(),
-                commit.committer().when().seconds(),
-            ))?;
-        }
+fn main() -> Result<()> {
+    let args = Cli::try_parse()?;
+    match args.cmd {
+        Command::Ingest(a) => run_ingest(a),
+        Command::Gc(a) => run_gc(a),
+        Command::Convert(a) => convert::convert(a.from_backend, a.from, a.to_backend, a.to),
+        Command::Archive(a) => run_archive(a),
+        Command::Index(a) => run_index(a),
+        Command::Ls(a) => run_ls(a),
+        Command::Cat(a) => run_cat(a),
+        Command::Diff(a) => run_diff(a),
+        Command::Search(a) => run_search(a),
     }
+}
 
-    tx.commit()?;
+fn run_ls(args: LsArgs) -> Result<()> {
+    let store = store::Store::open(args.db)?;
+    let commit_oid = Oid::from_str(&args.commit)?;
+    for entry in store.ls_tree(commit_oid, &args.path)? {
+        println!(
+            "{:?} {} {}",
+            entry.kind,
+            entry.oid,
+            String::from_utf8_lossy(&entry.name)
+        );
+    }
     Ok(())
 }
 
-fn insert_tree(
-    tree_entry_stmt: &mut rusqlite::Statement,
-    blob_exists_stmt: &mut rusqlite::Statement,
-    blob_insert_stmt: &mut rusqlite::Statement,
-    repo: &Repository,
-    tree: &Tree,
-) -> Result<()> {
-    for tree_obj in tree.into_iter() {
-        let new_entries = tree_entry_stmt.query_map(
-            (
-                tree.id().as_bytes(),
-                tree_obj.name_bytes(),
-                object_type_to_int(tree_obj.kind()),
-                tree_obj.id().as_bytes(),
-            ),
-            |row| TreeEntry::try_from(row),
-        )?;
-
-        let mut new: Option<TreeEntry> = None;
-        for new_entry in new_entries {
-            new = Some(new_entry?);
-        }
+fn run_cat(args: CatArgs) -> Result<()> {
+    let store = store::Store::open(args.db)?;
+    let commit_oid = Oid::from_str(&args.commit)?;
+    let oid = store.resolve_path(commit_oid, &args.path)?;
+    let content = store.read_blob(oid)?;
+    std::io::stdout().write_all(&content)?;
+    Ok(())
+}
+
+fn run_search(args: SearchArgs) -> Result<()> {
+    let conn = Connection::open(args.db)?;
+    for hit in search::search(&conn, &args.query)? {
+        println!("{}:{} ({})", hit.commit_oid, hit.path.display(), hit.oid);
+    }
+    Ok(())
+}
+
+fn run_diff(args: DiffArgs) -> Result<()> {
+    let conn = Connection::open(args.db)?;
+    let old_commit = Oid::from_str(&args.old_commit)?;
+    let new_commit = Oid::from_str(&args.new_commit)?;
 
-        if let Some(new_entry) = new {
-            if new_entry.kind == Some(ObjectType::Tree) {
-                insert_tree(
-                    tree_entry_stmt,
-                    blob_exists_stmt,
-                    blob_insert_stmt,
-                    repo,
-                    &repo.find_tree(new_entry.oid)?,
-                )?;
-            } else if new_entry.kind == Some(ObjectType::Blob) {
-                let exists = blob_exists_stmt
-                    .query_row((new_entry.oid.as_bytes(),), |row| row.get::<_, bool>(0))?;
-                if !exists {
-                    let dst = Vec::new();
-                    let mut enc = EncoderBuilder::new()
-                        // TODO: tune this. 16 seemed to not improve compression much.
-                        .level(10)
-                        .favor_dec_speed(true)
-                        .build(dst)?;
-                    let blob = repo.find_blob(new_entry.oid)?;
-                    enc.write_all(blob.content())?;
-                    let (dst, r) = enc.finish();
-                    r?;
-                    blob_insert_stmt.execute((new_entry.oid.as_bytes(), &dst))?;
+    for file_diff in diff::diff(&conn, old_commit, new_commit)? {
+        println!("diff {:?} {}", file_diff.status, file_diff.path.display());
+        for hunk in &file_diff.hunks {
+            println!("{}", hunk.header());
+            for line in &hunk.lines {
+                match line {
+                    diff::DiffLine::Context(s) => println!(" {}", s),
+                    diff::DiffLine::Added(s) => println!("+{}", s),
+                    diff::DiffLine::Removed(s) => println!("-{}", s),
                 }
             }
         }
     }
+
     Ok(())
 }
 
-fn object_type_to_int(kind: Option<ObjectType>) -> u8 {
-    match kind {
-        None => 0,
-        Some(ObjectType::Any) => 1,
-        Some(ObjectType::Commit) => 2,
-        Some(ObjectType::Tree) => 3,
-        Some(ObjectType::Blob) => 4,
-        Some(ObjectType::Tag) => 5,
-    }
+fn run_index(args: IndexArgs) -> Result<()> {
+    let mut conn = Connection::open(args.db)?;
+    conn.pragma_update_and_check(None, "journal_mode", "WAL", |_| Ok(()))?;
+    migrations().to_latest(&mut conn).context("migrating")?;
+
+    let indexed = search::backfill(&conn)?;
+    println!("indexed {} blobs", indexed);
+
+    Ok(())
 }
 
-fn object_type_from_int(val: u8) -> Option<ObjectType> {
-    match val {
-        0 => None,
-        1 => Some(ObjectType::Any),
-        2 => Some(ObjectType::Commit),
-        3 => Some(ObjectType::Tree),
-        4 => Some(ObjectType::Blob),
-        5 => Some(ObjectType::Tag),
-        _ => panic!("unknown"),
-    }
+fn run_archive(args: ArchiveArgs) -> Result<()> {
+    let conn = Connection::open(args.db)?;
+    let commit_oid = Oid::from_str(&args.commit)?;
+    let out = File::create(args.output)?;
+    archive::archive(&conn, commit_oid, args.format, out)
 }
 
-#[derive(Debug)]
-struct TreeEntry {
-    tree_oid: Oid,
-    name: Vec<u8>,
-    kind: Option<ObjectType>,
-    oid: Oid,
+fn run_gc(args: GcArgs) -> Result<()> {
+    let mut conn = Connection::open(args.db)?;
+    conn.pragma_update_and_check(None, "journal_mode", "WAL", |_| Ok(()))?;
+    migrations().to_latest(&mut conn).context("migrating")?;
+
+    let stats = gc::run(&mut conn, args.keep_newer.map(Duration::from_secs))?;
+    println!("{:?}", stats);
+
+    Ok(())
 }
 
-impl<'a> TryFrom<&Row<'a>> for TreeEntry {
-    type Error = rusqlite::Error;
+fn run_ingest(args: IngestArgs) -> Result<()> {
+    let mut conn = Connection::open(args.db)?;
+    conn.pragma_update_and_check(None, "journal_mode", "WAL", |_| Ok(()))?;
+    migrations().to_latest(&mut conn).context("migrating")?;
 
-    fn try_from(row: &Row) -> Result<Self, Self::Error> {
-        let tree_oid: Oid = row
-            .get::<_, [u8; 20]>(0)
-            .map(|arr| Oid::from_bytes(arr.as_slice()).unwrap())?;
-        let name: Vec<u8> = row.get(1)?;
-        let kind = object_type_from_int(row.get(2)?);
-        let oid: Oid = row
-            .get::<_, [u8; 20]>(3)
-            .map(|arr| Oid::from_bytes(arr.as_slice()).unwrap())?;
-        Ok(TreeEntry {
-            tree_oid,
-            name,
-            kind,
-            oid,
-        })
+    let repo = Repository::open(args.repo_path)?;
+
+    let changed_refs = compare_refs(&mut conn, args.repo_id, &repo, "refs/heads/*")?;
+    println!("{:?}", changed_refs);
+
+    let mut batcher = ingest::BatchIngestor::new(&repo, args.repo_id)
+        .index_text(args.index)
+        .on_commit(|event| {
+            println!(
+                "committed {} commits for {}, up to {}",
+                event.batch_commits, event.ref_name, event.last_commit
+            );
+            Ok(())
+        });
+
+    for diff in changed_refs {
+        let Some(new) = diff.new_target else {
+            continue;
+        };
+
+        let mut walker = repo.revwalk()?;
+        walker.set_sorting(Sort::TOPOLOGICAL | Sort::REVERSE)?;
+        walker.push(new)?;
+        if let Some(old) = diff.old_target {
+            walker.hide(old)?;
+        }
+
+        batcher.run(&mut conn, &diff.name, walker)?;
     }
+
+    Ok(())
 }
 
 #[derive(Debug)]
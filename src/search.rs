@@ -0,0 +1,128 @@
+use std::path::PathBuf;
+
+use anyhow::Result;
+use git2::Oid;
+use rusqlite::{Connection, OptionalExtension};
+
+use crate::backend::sqlite::{index_blob_text, looks_binary};
+
+/// A single blob matching a full-text search query, together with a commit
+/// and path it's reachable at. Blobs are content-addressed, so the same
+/// blob can live at more than one path (or in more than one commit); this
+/// only reports one.
+#[derive(Debug, Clone)]
+pub struct SearchHit {
+    pub oid: Oid,
+    pub commit_oid: Oid,
+    pub path: PathBuf,
+}
+
+/// Run an FTS5 `query` against the indexed blob content and resolve each
+/// hit to a concrete `(commit, path)` by walking `tree_entries` back up to
+/// a commit's root tree.
+pub fn search(conn: &Connection, query: &str) -> Result<Vec<SearchHit>> {
+    let mut stmt = conn.prepare(
+        "SELECT blob_fts_oid.oid
+         FROM blob_fts
+         JOIN blob_fts_oid ON blob_fts_oid.rowid = blob_fts.rowid
+         WHERE blob_fts.content MATCH ?",
+    )?;
+    let rows = stmt.query_map((query,), |row| row.get::<_, [u8; 20]>(0))?;
+
+    let mut hits = Vec::new();
+    for row in rows {
+        let oid = Oid::from_bytes(&row?)?;
+        if let Some((commit_oid, path)) = resolve_path(conn, oid)? {
+            hits.push(SearchHit { oid, commit_oid, path });
+        }
+    }
+
+    Ok(hits)
+}
+
+/// Walk `tree_entries` upward from `oid` (matching on the child `oid`
+/// column, since rows don't carry an explicit parent pointer otherwise)
+/// until the tree reached is some commit's root, reconstructing the path
+/// along the way. Returns `None` if `oid` isn't reachable from any commit
+/// currently stored (e.g. it was orphaned by a `gc` run).
+fn resolve_path(conn: &Connection, oid: Oid) -> Result<Option<(Oid, PathBuf)>> {
+    let mut components = Vec::new();
+    let mut current = oid;
+
+    loop {
+        let Some((parent_tree_oid, name)) = parent_of(conn, current)? else {
+            return Ok(None);
+        };
+        components.push(name);
+
+        if let Some(commit_oid) = commit_for_tree(conn, parent_tree_oid)? {
+            components.reverse();
+            let mut path = PathBuf::new();
+            for name in components {
+                path.push(String::from_utf8_lossy(&name).as_ref());
+            }
+            return Ok(Some((commit_oid, path)));
+        }
+
+        current = parent_tree_oid;
+    }
+}
+
+fn parent_of(conn: &Connection, oid: Oid) -> Result<Option<(Oid, Vec<u8>)>> {
+    conn.query_row(
+        "SELECT tree_oid, name FROM tree_entries WHERE oid = ? LIMIT 1",
+        (oid.as_bytes(),),
+        |row| {
+            let tree_oid: [u8; 20] = row.get(0)?;
+            let name: Vec<u8> = row.get(1)?;
+            Ok((tree_oid, name))
+        },
+    )
+    .optional()?
+    .map(|(tree_oid, name)| Ok::<_, anyhow::Error>((Oid::from_bytes(&tree_oid)?, name)))
+    .transpose()
+}
+
+fn commit_for_tree(conn: &Connection, tree_oid: Oid) -> Result<Option<Oid>> {
+    conn.query_row(
+        "SELECT oid FROM commits WHERE tree_oid = ? LIMIT 1",
+        (tree_oid.as_bytes(),),
+        |row| row.get::<_, [u8; 20]>(0),
+    )
+    .optional()?
+    .map(|oid| Oid::from_bytes(&oid).map_err(Into::into))
+    .transpose()
+}
+
+/// Index every blob that hasn't been indexed yet, for stores that were
+/// ingested before full-text search was enabled. Returns the number of
+/// blobs added to the index (binary blobs are skipped and not counted).
+pub fn backfill(conn: &Connection) -> Result<usize> {
+    let mut stmt = conn.prepare(
+        "SELECT oid, content_lz4 FROM blobs
+         WHERE oid NOT IN (SELECT oid FROM blob_fts_oid)",
+    )?;
+    let rows = stmt.query_map([], |row| {
+        let oid: [u8; 20] = row.get(0)?;
+        let content_lz4: Vec<u8> = row.get(1)?;
+        Ok((oid, content_lz4))
+    })?;
+
+    let mut indexed = 0;
+    for row in rows {
+        let (oid, content_lz4) = row?;
+        let oid = Oid::from_bytes(&oid)?;
+
+        let mut decoder = lz4::Decoder::new(content_lz4.as_slice())?;
+        let mut content = Vec::new();
+        std::io::Read::read_to_end(&mut decoder, &mut content)?;
+
+        if looks_binary(&content) {
+            continue;
+        }
+        index_blob_text(conn, oid, &content)?;
+        indexed += 1;
+    }
+
+    Ok(indexed)
+}
@@ -0,0 +1,147 @@
+use std::io::Read as _;
+use std::num::NonZeroUsize;
+use std::path::Path;
+use std::sync::Mutex;
+
+use anyhow::{anyhow, Result};
+use git2::{ObjectType, Oid};
+use lru::LruCache;
+use lz4::Decoder;
+use rusqlite::Connection;
+
+/// Default number of decompressed blobs kept around by [`Store`].
+const BLOB_CACHE_SIZE: usize = 512;
+
+/// Read-only access to an ingested repository, without needing the
+/// original git repo on disk.
+pub struct Store {
+    conn: Connection,
+    blob_cache: Mutex<LruCache<Oid, Vec<u8>>>,
+}
+
+/// A single entry of a tree, as returned by [`Store::ls_tree`].
+#[derive(Debug, Clone)]
+pub struct TreeEntry {
+    pub name: Vec<u8>,
+    pub kind: Option<ObjectType>,
+    pub oid: Oid,
+}
+
+impl Store {
+    pub fn open(db: impl AsRef<Path>) -> Result<Self> {
+        let conn = Connection::open(db)?;
+        Ok(Self {
+            conn,
+            blob_cache: Mutex::new(LruCache::new(
+                NonZeroUsize::new(BLOB_CACHE_SIZE).unwrap(),
+            )),
+        })
+    }
+
+    /// List the entries of the tree found at `path` within `commit_oid`.
+    /// An empty `path` lists the root tree.
+    pub fn ls_tree(&self, commit_oid: Oid, path: &Path) -> Result<Vec<TreeEntry>> {
+        let tree_oid = if path.as_os_str().is_empty() {
+            self.commit_tree_oid(commit_oid)?
+        } else {
+            self.resolve_path(commit_oid, path)?
+        };
+
+        let mut stmt = self
+            .conn
+            .prepare("SELECT name, kind, oid FROM tree_entries WHERE tree_oid = ?")?;
+        let rows = stmt.query_map((tree_oid.as_bytes(),), |row| {
+            let name: Vec<u8> = row.get(0)?;
+            let kind = object_type_from_int(row.get(1)?);
+            let oid: [u8; 20] = row.get(2)?;
+            Ok((name, kind, oid))
+        })?;
+
+        let mut entries = Vec::new();
+        for row in rows {
+            let (name, kind, oid) = row?;
+            entries.push(TreeEntry {
+                name,
+                kind,
+                oid: Oid::from_bytes(&oid)?,
+            });
+        }
+        Ok(entries)
+    }
+
+    /// Resolve a path within `commit_oid` to the oid of the tree or blob it
+    /// points at, walking `tree_entries` one directory component at a time.
+    pub fn resolve_path(&self, commit_oid: Oid, path: &Path) -> Result<Oid> {
+        let mut tree_oid = self.commit_tree_oid(commit_oid)?;
+
+        let components: Vec<_> = path.iter().collect();
+        let mut stmt = self.conn.prepare(
+            "SELECT kind, oid FROM tree_entries WHERE tree_oid = ? AND name = ?",
+        )?;
+
+        for (i, component) in components.iter().enumerate() {
+            let name = component.to_str().ok_or_else(|| anyhow!("non-utf8 path component"))?;
+            let (kind, oid) = stmt
+                .query_row((tree_oid.as_bytes(), name.as_bytes()), |row| {
+                    let kind = object_type_from_int(row.get(0)?);
+                    let oid: [u8; 20] = row.get(1)?;
+                    Ok((kind, oid))
+                })
+                .map_err(|_| anyhow!("no such path: {}", path.display()))?;
+            let oid = Oid::from_bytes(&oid)?;
+
+            let is_last = i == components.len() - 1;
+            if is_last {
+                return Ok(oid);
+            }
+            if kind != Some(ObjectType::Tree) {
+                return Err(anyhow!("{} is not a directory", path.display()));
+            }
+            tree_oid = oid;
+        }
+
+        Ok(tree_oid)
+    }
+
+    /// Decompress and return the content of the blob at `oid`, serving from
+    /// an in-memory LRU cache when possible.
+    pub fn read_blob(&self, oid: Oid) -> Result<Vec<u8>> {
+        if let Some(content) = self.blob_cache.lock().unwrap().get(&oid) {
+            return Ok(content.clone());
+        }
+
+        let content_lz4: Vec<u8> = self.conn.query_row(
+            "SELECT content_lz4 FROM blobs WHERE oid = ?",
+            (oid.as_bytes(),),
+            |row| row.get(0),
+        )?;
+
+        let mut decoder = Decoder::new(content_lz4.as_slice())?;
+        let mut content = Vec::new();
+        decoder.read_to_end(&mut content)?;
+
+        self.blob_cache.lock().unwrap().put(oid, content.clone());
+        Ok(content)
+    }
+
+    fn commit_tree_oid(&self, commit_oid: Oid) -> Result<Oid> {
+        let tree_oid: [u8; 20] = self.conn.query_row(
+            "SELECT tree_oid FROM commits WHERE oid = ?",
+            (commit_oid.as_bytes(),),
+            |row| row.get(0),
+        )?;
+        Ok(Oid::from_bytes(&tree_oid)?)
+    }
+}
+
+fn object_type_from_int(val: u8) -> Option<ObjectType> {
+    match val {
+        0 => None,
+        1 => Some(ObjectType::Any),
+        2 => Some(ObjectType::Commit),
+        3 => Some(ObjectType::Tree),
+        4 => Some(ObjectType::Blob),
+        5 => Some(ObjectType::Tag),
+        _ => panic!("unknown"),
+    }
+}